@@ -1,10 +1,7 @@
-use std::{
-    fs::File,
-    io::BufRead,
-    io::{self},
-    marker::PhantomData,
-    path::Path,
-};
+use std::{fs::File, io, io::BufRead, io::Read, path::Path, thread::sleep, time::Duration};
+
+const PLAYFIELD_WIDTH: usize = 80;
+const PLAYFIELD_HEIGHT: usize = 25;
 
 fn read_lines<P>(filename: P) -> io::Result<io::Lines<io::BufReader<File>>>
 where
@@ -16,20 +13,68 @@ where
     }
 }
 
-fn parse_program(filename: &str) -> Vec<String> {
-    read_lines(filename)
+fn wrap(n: i32, bound: i32) -> i32 {
+    ((n % bound) + bound) % bound
+}
+
+/// A small xorshift64 PRNG, seeded from the system clock, used only to pick
+/// a random `Direction` for `?` - not suitable for anything security-sensitive.
+struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    fn new() -> Self {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+
+        Self {
+            state: seed | 1, // xorshift requires a non-zero state
+        }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.state;
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.state = x;
+        x
+    }
+
+    fn next_direction(&mut self) -> Direction {
+        match self.next_u64() % 4 {
+            0 => Direction::Up,
+            1 => Direction::Down,
+            2 => Direction::Left,
+            _ => Direction::Right,
+        }
+    }
+}
+
+fn pad_line(line: &str, width: usize) -> Vec<char> {
+    let mut chars: Vec<char> = line.chars().collect();
+    chars.resize(width, ' ');
+    chars
+}
+
+fn parse_program(filename: &str) -> Vec<Vec<char>> {
+    let lines = read_lines(filename)
         .expect("Something went wrong reading the source")
         .collect::<Result<Vec<_>, _>>()
-        .expect("Single line failed to unwrap?")
-}
+        .expect("Single line failed to unwrap?");
 
-fn put_ret<T>(v: Vec<T>, val: T) -> Vec<T>
-where
-    T: Clone,
-{
-    let mut d = v;
-    d.push(val);
-    d
+    let mut playfield: Vec<Vec<char>> = lines
+        .iter()
+        .map(|line| pad_line(line, PLAYFIELD_WIDTH))
+        .collect();
+
+    playfield.resize(PLAYFIELD_HEIGHT, vec![' '; PLAYFIELD_WIDTH]);
+    playfield
 }
 
 struct ProgramPosition {
@@ -66,15 +111,27 @@ enum Operator {
     Subtraction,    // -	Subtraction: Pop a and b, then push b-a
     Multiplication, // *	Multiplication: Pop a and b, then push a*b
     Division,       // /	Integer division: Pop a and b, then push b/a, rounded towards 0.
+    Modulo,         // %	Modulo: Pop a and b, then push b%a
+    Not,            // !	Logical NOT: Pop a, push 1 if a is 0, else push 0
+    GreaterThan,    // `	Greater than: Pop a and b, then push 1 if b>a, else push 0
 
     ToggleStringMode, // start/stop interpreting program data as a string on ""
 
-    Pop,
     Duplicate,
+    Swap,    // \	Swap: Swap the top two values on the stack
+    Discard, // $	Discard: Pop and discard the top of the stack
     PopMoveHorizontal,
     PopMoveVertical,
 
     Get,
+    Put,
+
+    OutputInteger, // .	Output: Pop the top of the stack and print it as a decimal integer, followed by a space
+    OutputChar,    // ,	Output: Pop the top of the stack and print it as a character
+
+    ReadInteger,     // &	Read an integer from stdin and push it
+    ReadChar,        // ~	Read a character from stdin and push its ASCII value
+    RandomDirection, // ?	Pick one of the four directions uniformly at random
 
     SetDirection(Direction),
 
@@ -101,15 +158,26 @@ fn parse_operator(reader_mode: ReaderMode, c: char) -> Operator {
             '-' => Operator::Subtraction,
             '*' => Operator::Multiplication,
             '/' => Operator::Division,
+            '%' => Operator::Modulo,
+            '!' => Operator::Not,
+            '`' => Operator::GreaterThan,
 
             '\"' => Operator::ToggleStringMode,
 
             ':' => Operator::Duplicate,
+            '\\' => Operator::Swap,
+            '$' => Operator::Discard,
 
-            ',' => Operator::Pop,
+            '.' => Operator::OutputInteger,
+            ',' => Operator::OutputChar,
             '_' => Operator::PopMoveHorizontal,
             '|' => Operator::PopMoveVertical,
             'g' => Operator::Get,
+            'p' => Operator::Put,
+
+            '&' => Operator::ReadInteger,
+            '~' => Operator::ReadChar,
+            '?' => Operator::RandomDirection,
 
             '>' => Operator::SetDirection(Direction::Right),
             '<' => Operator::SetDirection(Direction::Left),
@@ -129,9 +197,56 @@ fn parse_operator(reader_mode: ReaderMode, c: char) -> Operator {
     }
 }
 
-fn mathematical_operation<F>(stack: Vec<i32>, operation: F) -> Vec<i32>
+#[derive(Debug, Clone)]
+enum InterpreterError {
+    StackUnderflow { row: i32, col: i32 },
+    UnknownOperator { operator: char, row: i32, col: i32 },
+    DivisionByZero { row: i32, col: i32 },
+    OutOfBounds { row: i32, col: i32 },
+}
+
+impl InterpreterError {
+    fn message(&self) -> String {
+        match self {
+            InterpreterError::StackUnderflow { .. } => "stack underflow".to_string(),
+            InterpreterError::UnknownOperator { operator, .. } => {
+                format!("unknown operator '{}'", operator)
+            }
+            InterpreterError::DivisionByZero { .. } => "division by zero".to_string(),
+            InterpreterError::OutOfBounds { .. } => "instruction pointer out of bounds".to_string(),
+        }
+    }
+
+    fn position(&self) -> (i32, i32) {
+        match *self {
+            InterpreterError::StackUnderflow { row, col } => (row, col),
+            InterpreterError::UnknownOperator { row, col, .. } => (row, col),
+            InterpreterError::DivisionByZero { row, col } => (row, col),
+            InterpreterError::OutOfBounds { row, col } => (row, col),
+        }
+    }
+}
+
+fn report_error(error: &InterpreterError, program: &[Vec<char>]) -> String {
+    let (row, col) = error.position();
+
+    let line: String = program
+        .get(row as usize)
+        .map(|chars| chars.iter().collect())
+        .unwrap_or_default();
+
+    let caret = format!("{}^", " ".repeat(col.max(0) as usize));
+
+    format!("Error: {}\n{}\n{}", error.message(), line, caret)
+}
+
+fn mathematical_operation<F>(
+    stack: Vec<i32>,
+    position: &ProgramPosition,
+    operation: F,
+) -> Result<Vec<i32>, InterpreterError>
 where
-    F: Fn(i32, i32) -> i32,
+    F: Fn(i32, i32) -> Result<i32, InterpreterError>,
 {
     let mut data = stack;
     let opx = data.pop();
@@ -139,253 +254,553 @@ where
 
     match (opx, opy) {
         (Some(a), Some(b)) => {
-            data.push(operation(a, b));
-            data
+            data.push(operation(a, b)?);
+            Ok(data)
         }
-        _ => {
-            panic!("Attempted to do math with: {:?} {:?}", opx, opy);
+        _ => Err(InterpreterError::StackUnderflow {
+            row: position.row,
+            col: position.col,
+        }),
+    }
+}
+
+/// The playfield compiled to a flat, row-major array of instructions.
+///
+/// Execution reads straight out of `code`/`raw` by index instead of walking
+/// `Vec<Vec<char>>` and re-parsing a cell's character on every visit. `raw`
+/// is kept alongside the compiled `Operator` because string mode (`"`)
+/// reinterprets the same character as a literal push rather than an
+/// operator, and `p` can rewrite a cell at runtime - when that happens we
+/// only recompile the touched cell (see `Chunk::patch`) rather than
+/// rebuilding the whole chunk.
+///
+/// `raw`/`code` are always exactly `width * height` long, row-major - ragged
+/// input rows are padded with spaces at compile time so `index()` never has
+/// to special-case a short row.
+struct Chunk {
+    width: usize,
+    height: usize,
+    raw: Vec<char>,
+    code: Vec<Operator>,
+}
+
+impl Chunk {
+    fn compile(program: &[Vec<char>]) -> Self {
+        let height = program.len();
+        let width = program.iter().map(|row| row.len()).max().unwrap_or(0);
+
+        let raw: Vec<char> = program
+            .iter()
+            .flat_map(|row| {
+                row.iter()
+                    .copied()
+                    .chain(std::iter::repeat_n(' ', width - row.len()))
+            })
+            .collect();
+        debug_assert_eq!(raw.len(), width * height);
+
+        let code = raw
+            .iter()
+            .map(|&c| parse_operator(ReaderMode::Normal, c))
+            .collect();
+
+        Self {
+            width,
+            height,
+            raw,
+            code,
+        }
+    }
+
+    fn in_bounds(&self, row: i32, col: i32) -> bool {
+        row >= 0 && col >= 0 && (row as usize) < self.height && (col as usize) < self.width
+    }
+
+    fn index(&self, row: i32, col: i32) -> usize {
+        debug_assert!(self.in_bounds(row, col));
+        row as usize * self.width + col as usize
+    }
+
+    fn fetch(&self, row: i32, col: i32, mode: ReaderMode) -> Result<Operator, InterpreterError> {
+        if !self.in_bounds(row, col) {
+            return Err(InterpreterError::OutOfBounds { row, col });
+        }
+        let idx = self.index(row, col);
+
+        Ok(match (self.raw[idx], mode) {
+            ('\"', _) => Operator::ToggleStringMode,
+            (c, ReaderMode::String) => Operator::PushAsciiValue(c as u8),
+            (_, ReaderMode::Normal) => self.code[idx].clone(),
+        })
+    }
+
+    fn cell_at(&self, row: i32, col: i32) -> char {
+        if self.in_bounds(row, col) {
+            self.raw[self.index(row, col)]
+        } else {
+            ' '
+        }
+    }
+
+    fn patch(&mut self, row: i32, col: i32, c: char) {
+        if self.in_bounds(row, col) {
+            let idx = self.index(row, col);
+            self.raw[idx] = c;
+            self.code[idx] = parse_operator(ReaderMode::Normal, c);
         }
     }
 }
 
-#[derive(Clone, Debug)]
-struct InterpreterState {
-    direction: Direction,
+/// A snapshot of the VM's visible state after a single step, cheap enough to
+/// hand to a caller every instruction without cloning the playfield itself.
+#[derive(Debug, Clone)]
+struct VmStep {
+    row: i32,
+    col: i32,
+    stack: Vec<i32>,
+    output: Vec<char>,
+}
+
+/// Executes a compiled `Chunk` with a single mutable stack/ip/direction,
+/// rather than rebuilding an `InterpreterState` (program, stack and output
+/// included) on every instruction.
+struct Vm<R: Read> {
+    chunk: Chunk,
     row: i32,
     col: i32,
+    direction: Direction,
     mode: ReaderMode,
     stack: Vec<i32>,
-    program: Vec<String>,
     output: Vec<char>,
     terminated: bool,
+    stdin: std::iter::Peekable<io::Bytes<io::BufReader<R>>>,
+    rng: Rng,
 }
 
-impl InterpreterState {
-    fn new(program: Vec<String>) -> Self {
+impl Vm<io::Stdin> {
+    fn new(chunk: Chunk) -> Self {
+        Self::with_reader(chunk, io::stdin())
+    }
+}
+
+impl<R: Read> Vm<R> {
+    /// Builds a `Vm` reading `&`/`~` input from `reader` instead of stdin -
+    /// lets tests feed a `Cursor<&[u8]>` rather than being stuck with the
+    /// process's real stdin.
+    fn with_reader(chunk: Chunk, reader: R) -> Self {
         Self {
-            direction: Direction::Right,
+            chunk,
             row: 0,
             col: 0,
+            direction: Direction::Right,
             mode: ReaderMode::Normal,
             stack: Vec::new(),
-            program,
             output: Vec::new(),
             terminated: false,
+            stdin: io::BufReader::new(reader).bytes().peekable(),
+            rng: Rng::new(),
         }
     }
-}
 
-fn get_operator(
-    program: Vec<String>,
-    ProgramPosition { row, col }: ProgramPosition,
-    reader_mode: ReaderMode,
-) -> Operator {
-    let line = program.get(row as usize).expect("Valid Line");
-    let operator = line.chars().nth(col as usize).expect("Valid column");
+    fn read_integer(&mut self) -> i32 {
+        while let Some(Ok(b)) = self.stdin.peek() {
+            if (*b as char).is_ascii_whitespace() {
+                self.stdin.next();
+            } else {
+                break;
+            }
+        }
 
-    parse_operator(reader_mode, operator)
-}
+        let negative = matches!(self.stdin.peek(), Some(Ok(b'-')));
+        if negative {
+            self.stdin.next();
+        }
 
-trait Interpretable<S, Op> {
-    fn next_operation(s: S) -> Option<Op>;
-    fn interpret(s: S, op: Op) -> S;
-}
+        let mut value = 0i32;
+        let mut read_any = false;
 
-#[derive(Debug)]
-struct Interpreter<State, Op> {
-    state: State,
-    _op: PhantomData<Op>,
-}
+        while let Some(Ok(b)) = self.stdin.peek() {
+            if b.is_ascii_digit() {
+                value = value.saturating_mul(10).saturating_add((b - b'0') as i32);
+                read_any = true;
+                self.stdin.next();
+            } else {
+                break;
+            }
+        }
 
-impl Interpretable<InterpreterState, Operator> for Interpreter<InterpreterState, Operator> {
-    fn next_operation(s: InterpreterState) -> Option<Operator> {
-        if !s.terminated {
-            let position = ProgramPosition {
-                row: s.row,
-                col: s.col,
-            };
+        if !read_any {
+            return -1;
+        }
 
-            let operator = get_operator(s.program.clone(), position, s.mode);
-            Some(operator)
+        if negative {
+            -value
         } else {
-            None
+            value
         }
     }
 
-    fn interpret(state: InterpreterState, operator: Operator) -> InterpreterState {
-        let partial_update = match operator {
-            Operator::PushDigit(d) => InterpreterState {
-                stack: {
-                    let mut next = state.stack.clone();
-                    next.push(d as i32);
-                    next
-                },
-                ..state
-            },
-            Operator::PushAsciiValue(c) => {
-                let mut new_stack = state.stack.clone();
-                new_stack.push(c as i32);
-
-                InterpreterState {
-                    stack: {
-                        let mut next = state.stack.clone();
-                        next.push(c as i32);
-                        next
-                    },
-                    ..state
-                }
-            }
-            Operator::Addition => InterpreterState {
-                stack: mathematical_operation(state.stack, |x, y| x + y),
-                ..state
-            },
-            Operator::Subtraction => InterpreterState {
-                stack: mathematical_operation(state.stack, |x, y| y - x),
-                ..state
-            },
-            Operator::Multiplication => InterpreterState {
-                stack: mathematical_operation(state.stack, |x, y| x * y),
-                ..state
-            },
-            Operator::Division => InterpreterState {
-                stack: mathematical_operation(state.stack, |x, y| y / x),
-                ..state
-            },
+    fn read_char(&mut self) -> i32 {
+        match self.stdin.next() {
+            Some(Ok(b)) => b as i32,
+            _ => -1,
+        }
+    }
 
-            Operator::Duplicate => {
-                let mut new_stack = state.stack.clone();
+    fn pop(&mut self, span: &ProgramPosition) -> Result<i32, InterpreterError> {
+        self.stack.pop().ok_or(InterpreterError::StackUnderflow {
+            row: span.row,
+            col: span.col,
+        })
+    }
 
-                if !new_stack.is_empty() {
-                    let last = new_stack.last().expect("Nothing to duplicate").to_owned();
-                    new_stack.push(last);
-                }
+    fn apply_binary<F>(
+        &mut self,
+        span: &ProgramPosition,
+        operation: F,
+    ) -> Result<(), InterpreterError>
+    where
+        F: Fn(i32, i32) -> Result<i32, InterpreterError>,
+    {
+        let stack = std::mem::take(&mut self.stack);
+        self.stack = mathematical_operation(stack, span, operation)?;
+        Ok(())
+    }
 
-                InterpreterState {
-                    stack: new_stack,
-                    ..state
+    fn step(&mut self) -> Result<(), InterpreterError> {
+        let span = ProgramPosition {
+            row: self.row,
+            col: self.col,
+        };
+        let operator = self.chunk.fetch(self.row, self.col, self.mode)?;
+
+        match operator {
+            Operator::PushDigit(d) => self.stack.push(d as i32),
+            Operator::PushAsciiValue(c) => self.stack.push(c as i32),
+
+            Operator::Addition => self.apply_binary(&span, |x, y| Ok(x + y))?,
+            Operator::Subtraction => self.apply_binary(&span, |x, y| Ok(y - x))?,
+            Operator::Multiplication => self.apply_binary(&span, |x, y| Ok(x * y))?,
+            Operator::Division => self.apply_binary(&span, |x, y| {
+                if x == 0 {
+                    Err(InterpreterError::DivisionByZero {
+                        row: span.row,
+                        col: span.col,
+                    })
+                } else {
+                    Ok(y / x)
+                }
+            })?,
+            Operator::Modulo => self.apply_binary(&span, |x, y| {
+                if x == 0 {
+                    Err(InterpreterError::DivisionByZero {
+                        row: span.row,
+                        col: span.col,
+                    })
+                } else {
+                    Ok(y % x)
                 }
+            })?,
+            Operator::Not => {
+                let out = self.stack.pop().unwrap_or(0);
+                self.stack.push(if out == 0 { 1 } else { 0 });
+            }
+            Operator::GreaterThan => {
+                self.apply_binary(&span, |x, y| Ok(if y > x { 1 } else { 0 }))?
             }
 
-            Operator::Pop => {
-                let mut new_stack = state.stack.clone();
-                let out = new_stack.pop().expect("No value to Pop") as u8;
-                let out = char::from(out);
-
-                InterpreterState {
-                    stack: new_stack,
-                    output: put_ret(state.output, out),
-                    ..state
+            Operator::Duplicate => {
+                if let Some(&last) = self.stack.last() {
+                    self.stack.push(last);
                 }
             }
+            Operator::Swap => {
+                let a = self.stack.pop().unwrap_or(0);
+                let b = self.stack.pop().unwrap_or(0);
+                self.stack.push(a);
+                self.stack.push(b);
+            }
+            Operator::Discard => {
+                self.pop(&span)?;
+            }
+            Operator::OutputInteger => {
+                let out = self.pop(&span)?;
+                self.output.extend(out.to_string().chars());
+                self.output.push(' ');
+            }
+            Operator::OutputChar => {
+                let out = self.pop(&span)? as u8;
+                self.output.push(char::from(out));
+            }
             Operator::PopMoveHorizontal => {
-                let mut new_stack = state.stack.clone();
-                let out = new_stack.pop().unwrap_or(0);
-
-                InterpreterState {
-                    stack: new_stack,
-                    direction: match out {
-                        0 => Direction::Right,
-                        _ => Direction::Left,
-                    },
-                    ..state
-                }
+                let out = self.stack.pop().unwrap_or(0);
+                self.direction = match out {
+                    0 => Direction::Right,
+                    _ => Direction::Left,
+                };
             }
             Operator::PopMoveVertical => {
-                let mut new_stack = state.stack.clone();
-                let out = new_stack.pop().unwrap_or(0);
-
-                InterpreterState {
-                    stack: new_stack,
-                    direction: match out {
-                        0 => Direction::Down,
-                        _ => Direction::Up,
-                    },
-                    ..state
-                }
+                let out = self.stack.pop().unwrap_or(0);
+                self.direction = match out {
+                    0 => Direction::Down,
+                    _ => Direction::Up,
+                };
             }
             Operator::Get => {
-                let expect_message = "Cannot perform get with less than 2 items on the stack";
-                let mut stack = state.stack.clone();
-                let y = stack.pop().expect(expect_message);
-                let x = stack.pop().expect(expect_message);
-
-                let s = state.program.get(x as usize).map(|s| s.to_owned());
-
-                let c = match s {
-                    Some(s) => s.chars().into_iter().nth(y as usize).unwrap_or(0 as char) as i32,
-                    None => 0,
+                let y = self.pop(&span)?;
+                let x = self.pop(&span)?;
+                self.stack.push(self.chunk.cell_at(x, y) as i32);
+            }
+            Operator::Put => {
+                let y = self.pop(&span)?;
+                let x = self.pop(&span)?;
+                let v = self.pop(&span)?;
+                self.chunk.patch(x, y, char::from(v as u8));
+            }
+            Operator::ReadInteger => {
+                let v = self.read_integer();
+                self.stack.push(v);
+            }
+            Operator::ReadChar => {
+                let v = self.read_char();
+                self.stack.push(v);
+            }
+            Operator::RandomDirection => self.direction = self.rng.next_direction(),
+            Operator::ToggleStringMode => {
+                self.mode = match self.mode {
+                    ReaderMode::String => ReaderMode::Normal,
+                    ReaderMode::Normal => ReaderMode::String,
                 };
-
-                stack.push(c);
-
-                InterpreterState { stack, ..state }
             }
-            Operator::ToggleStringMode => InterpreterState {
-                mode: {
-                    match state.mode {
-                        ReaderMode::String => ReaderMode::Normal,
-                        ReaderMode::Normal => ReaderMode::String,
-                    }
-                },
-                ..state
-            },
-            Operator::SetDirection(direction) => InterpreterState { direction, ..state },
+            Operator::SetDirection(direction) => self.direction = direction,
             Operator::Bridge => {
-                let mv = Direction::get_move(state.direction);
-                InterpreterState {
-                    row: state.row + mv.row,
-                    col: state.col + mv.col,
-                    ..state
-                }
+                let mv = Direction::get_move(self.direction);
+                self.row += mv.row;
+                self.col += mv.col;
+            }
+            Operator::NoOp => {}
+            Operator::End => {
+                self.terminated = true;
+                return Ok(());
+            }
+            Operator::Unknown(c) => {
+                return Err(InterpreterError::UnknownOperator {
+                    operator: c,
+                    row: span.row,
+                    col: span.col,
+                })
             }
-            Operator::NoOp => state,
-            Operator::End => InterpreterState {
-                terminated: true,
-                ..state
-            },
-            Operator::Unknown(c) => panic!("We didn't know what to do here. Operator: {}", c),
         };
 
-        let mv = Direction::get_move(partial_update.direction);
+        let mv = Direction::get_move(self.direction);
+        self.row = wrap(self.row + mv.row, self.chunk.height as i32);
+        self.col = wrap(self.col + mv.col, self.chunk.width as i32);
 
-        InterpreterState {
-            row: partial_update.row + mv.row,
-            col: partial_update.col + mv.col,
-            stack: partial_update.stack.clone(),
-            output: partial_update.output.clone(),
-            program: partial_update.program.clone(),
-            ..partial_update
-        }
+        Ok(())
     }
 }
 
-impl Iterator for Interpreter<InterpreterState, Operator> {
-    type Item = InterpreterState;
+impl<R: Read> Iterator for Vm<R> {
+    type Item = Result<VmStep, InterpreterError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        Self::next_operation(self.state.clone())
-            .map(|operator| Self::interpret(self.state.clone(), operator))
-            .map(|state| {
-                self.state = state;
-                self.state.clone()
-            })
+        if self.terminated {
+            return None;
+        }
+
+        let result = self.step().map(|()| VmStep {
+            row: self.row,
+            col: self.col,
+            stack: self.stack.clone(),
+            output: self.output.clone(),
+        });
+
+        if result.is_err() {
+            self.terminated = true;
+        }
+
+        Some(result)
     }
 }
 
 fn main() {
     let filename = "./hello-world.bf";
     let program = parse_program(filename);
+    let chunk = Chunk::compile(&program);
+
+    for result in Vm::new(chunk) {
+        match result {
+            Ok(step) => {
+                sleep(Duration::from_millis(32));
+                println!(
+                    "Result:\tRow: {}, Col: {}\t Stack: {:?} Output: {:?}",
+                    step.row, step.col, step.stack, step.output
+                );
+            }
+            Err(e) => {
+                eprintln!("{}", report_error(&e, &program));
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn compile(source: &str) -> Chunk {
+        let program: Vec<Vec<char>> = source.lines().map(|line| line.chars().collect()).collect();
+        Chunk::compile(&program)
+    }
+
+    fn run(source: &str) -> Vm<Cursor<Vec<u8>>> {
+        run_with_input(source, "")
+    }
+
+    fn run_with_input(source: &str, input: &str) -> Vm<Cursor<Vec<u8>>> {
+        let mut vm = Vm::with_reader(compile(source), Cursor::new(input.as_bytes().to_vec()));
+        for result in &mut vm {
+            if result.is_err() {
+                break;
+            }
+        }
+        vm
+    }
+
+    #[test]
+    fn report_error_formats_message_line_and_caret() {
+        let program = vec![vec!['1', 'z', '@']];
+        let error = InterpreterError::UnknownOperator {
+            operator: 'z',
+            row: 0,
+            col: 1,
+        };
+
+        assert_eq!(
+            report_error(&error, &program),
+            "Error: unknown operator 'z'\n1z@\n ^"
+        );
+    }
 
-    let interpreter = Interpreter {
-        state: InterpreterState::new(program),
-        _op: PhantomData::<Operator>,
-    };
+    #[test]
+    fn put_and_get_round_trip_through_the_same_cell() {
+        // '5' '0' '3' push v=5, x=0, y=3; 'p' writes it into cell (0, 3);
+        // '0' '3' 'g' reads it back and '.' prints it as an integer.
+        let vm = run("503p03g.@");
+        assert_eq!(vm.output, vec!['5', ' ']);
+    }
 
-    for state in interpreter {
-        sleep(Duration::from_millis(32));
-        println!(
-            "Result:\tRow: {}, Col: {}\t Stack: {:?} Output: {:?}",
-            state.row, state.col, state.stack, state.output
+    #[test]
+    fn ip_wraps_toroidally_at_all_four_edges() {
+        assert_eq!(wrap(-1, 3), 2, "left/up edge should wrap to the far side");
+        assert_eq!(
+            wrap(3, 3),
+            0,
+            "right/down edge should wrap to the near side"
         );
+        assert_eq!(wrap(0, 3), 0, "in-bounds values are unaffected");
+        assert_eq!(
+            wrap(-4, 3),
+            2,
+            "wrapping handles more than one bound's worth of underflow"
+        );
+    }
+
+    fn last_result(source: &str) -> Result<VmStep, InterpreterError> {
+        let mut vm = Vm::with_reader(compile(source), Cursor::new(Vec::new()));
+        let mut last = None;
+        for result in &mut vm {
+            let failed = result.is_err();
+            last = Some(result);
+            if failed {
+                break;
+            }
+        }
+        last.expect("program should execute at least one instruction")
+    }
+
+    #[test]
+    fn division_by_zero_is_reported_as_an_interpreter_error() {
+        assert!(matches!(
+            last_result("50/.@"),
+            Err(InterpreterError::DivisionByZero { .. })
+        ));
+    }
+
+    #[test]
+    fn modulo_by_zero_is_reported_as_an_interpreter_error() {
+        assert!(matches!(
+            last_result("50%.@"),
+            Err(InterpreterError::DivisionByZero { .. })
+        ));
+    }
+
+    #[test]
+    fn not_negates_a_zero_or_nonzero_top_of_stack() {
+        let zero = run("0!.@");
+        assert_eq!(zero.output, vec!['1', ' ']);
+
+        let nonzero = run("1!.@");
+        assert_eq!(nonzero.output, vec!['0', ' ']);
+    }
+
+    #[test]
+    fn greater_than_pushes_one_when_the_second_from_top_is_bigger() {
+        let vm = run("32`.@");
+        assert_eq!(vm.output, vec!['1', ' ']);
+    }
+
+    #[test]
+    fn swap_exchanges_the_top_two_stack_values() {
+        let vm = run("12\\..@");
+        assert_eq!(vm.output, vec!['1', ' ', '2', ' ']);
+    }
+
+    #[test]
+    fn swap_treats_a_missing_second_value_as_zero() {
+        let vm = run("5\\..@");
+        assert_eq!(vm.output, vec!['0', ' ', '5', ' ']);
+    }
+
+    #[test]
+    fn discard_drops_the_top_of_the_stack() {
+        let vm = run("12$.@");
+        assert_eq!(vm.output, vec!['1', ' ']);
+    }
+
+    fn vm_with_input(input: &str) -> Vm<Cursor<Vec<u8>>> {
+        Vm::with_reader(compile("@"), Cursor::new(input.as_bytes().to_vec()))
+    }
+
+    #[test]
+    fn read_integer_parses_a_plain_integer() {
+        assert_eq!(vm_with_input("42").read_integer(), 42);
+    }
+
+    #[test]
+    fn read_integer_parses_a_negative_integer() {
+        assert_eq!(vm_with_input("-7").read_integer(), -7);
+    }
+
+    #[test]
+    fn read_integer_skips_leading_whitespace() {
+        assert_eq!(vm_with_input("   9").read_integer(), 9);
+    }
+
+    #[test]
+    fn read_integer_returns_minus_one_at_eof() {
+        assert_eq!(vm_with_input("").read_integer(), -1);
+    }
+
+    #[test]
+    fn read_char_reads_the_next_byte() {
+        assert_eq!(vm_with_input("A").read_char(), 'A' as i32);
+    }
+
+    #[test]
+    fn read_char_returns_minus_one_at_eof() {
+        assert_eq!(vm_with_input("").read_char(), -1);
     }
 }